@@ -1,44 +1,163 @@
-use std::path::{Path, PathBuf};
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use log::debug;
 use walkdir::WalkDir;
 
-use crate::{cluster::cluster_globs, walk::MultiGlobWalker, GlobError};
+use crate::{cluster::cluster_globs, walk::MultiGlobWalker, DirEntry, Error};
+
+/// Restricts which kind of filesystem entries are yielded by a [`MultiGlobWalker`].
+///
+/// This only filters which entries are emitted; directories are always descended
+/// into regardless of this setting (so e.g. `Files` can still find files nested
+/// deeper than a non-matching directory).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkType {
+    /// Yield all entries, regardless of type. This is the default.
+    #[default]
+    All,
+    /// Only yield regular files.
+    Files,
+    /// Only yield directories.
+    Dirs,
+    /// Only yield symbolic links.
+    Symlinks,
+}
+
+impl WalkType {
+    pub(crate) fn matches(self, entry: &DirEntry) -> bool {
+        match self {
+            Self::All => true,
+            Self::Files => entry.file_type().is_file(),
+            Self::Dirs => entry.file_type().is_dir(),
+            Self::Symlinks => entry.path_is_symlink(),
+        }
+    }
+}
+
+/// A compiled set of exclusion patterns, matched against a path relative to
+/// the walker's base directory.
+#[derive(Clone)]
+pub(crate) struct ExcludeSet {
+    pub globset: GlobSet,
+    /// Whether each pattern (in the same order as `globset`'s matches) contains `**`.
+    pub recursive: Vec<bool>,
+}
+
+impl ExcludeSet {
+    pub fn build(patterns: &[String], skip_invalid: bool) -> Result<Self, Error> {
+        let mut builder = GlobSetBuilder::new();
+        let mut recursive = Vec::new();
+        for pattern in patterns {
+            let glob = match Glob::new(pattern) {
+                Ok(glob) => glob,
+                Err(_) if skip_invalid => continue,
+                Err(err) => return Err(err.into()),
+            };
+            builder.add(glob);
+            recursive.push(pattern.contains("**"));
+        }
+        let globset = match builder.build() {
+            Ok(globset) => globset,
+            Err(_) if skip_invalid => {
+                recursive.clear();
+                GlobSet::empty()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self { globset, recursive })
+    }
+}
+
+/// A user-supplied predicate consulted for every candidate entry; see
+/// [`MultiGlobBuilder::filter_entry`].
+pub(crate) type FilterEntryFn = Arc<dyn Fn(&DirEntry) -> bool + Send + Sync + 'static>;
+
+/// A user-supplied callback invoked once per directory listing; see
+/// [`MultiGlobBuilder::process_read_dir`].
+pub(crate) type ProcessReadDirFn =
+    Arc<dyn Fn(usize, &Path, &mut Vec<DirEntry>) + Send + Sync + 'static>;
+
+/// A user-supplied comparator for ordering directory siblings; see
+/// [`MultiGlobBuilder::sort_by`].
+pub(crate) type SortByFn = Arc<dyn Fn(&DirEntry, &DirEntry) -> Ordering + Send + Sync + 'static>;
+
+/// How siblings within a directory are ordered before being matched and yielded.
+#[derive(Clone)]
+pub(crate) enum SortOrder {
+    /// Sort by file name; the default.
+    FileName,
+    /// Sort with a user-supplied comparator; see [`MultiGlobBuilder::sort_by`].
+    Custom(SortByFn),
+}
 
 #[derive(Clone)]
 pub(crate) struct MultiGlobOptions {
     pub follow_links: bool,
+    pub follow_root_links: Option<bool>,
     pub max_depth: usize,
+    pub min_depth: usize,
     pub max_open: usize,
     pub same_file_system: bool,
     pub case_insensitive: bool,
+    pub exclude: Option<ExcludeSet>,
+    pub walk_type: WalkType,
+    pub detect_loops: bool,
+    pub metadata_batch_size: usize,
+    pub parallelism: usize,
+    pub filter_entry: Option<FilterEntryFn>,
+    pub process_read_dir: Option<ProcessReadDirFn>,
+    pub sort: SortOrder,
 }
 
 impl Default for MultiGlobOptions {
     fn default() -> Self {
         Self {
             follow_links: false,
+            follow_root_links: None,
             max_depth: usize::MAX,
+            min_depth: 0,
             max_open: 10,
             same_file_system: false,
             case_insensitive: false,
+            exclude: None,
+            walk_type: WalkType::default(),
+            detect_loops: false,
+            metadata_batch_size: 32,
+            parallelism: 0,
+            filter_entry: None,
+            process_read_dir: None,
+            sort: SortOrder::FileName,
         }
     }
 }
 
 impl MultiGlobOptions {
     pub fn configure_walkdir(&self, walkdir: WalkDir) -> WalkDir {
-        walkdir
-            .sort_by_file_name()
+        let walkdir = walkdir
             .follow_links(self.follow_links)
             .max_open(self.max_open)
-            .same_file_system(self.same_file_system)
+            .same_file_system(self.same_file_system);
+        match &self.sort {
+            SortOrder::FileName => walkdir.sort_by_file_name(),
+            SortOrder::Custom(cmp) => {
+                let cmp = cmp.clone();
+                walkdir.sort_by(move |a, b| {
+                    cmp(&DirEntry::from(a.clone()), &DirEntry::from(b.clone()))
+                })
+            }
+        }
     }
 }
 
 pub struct MultiGlobBuilder {
     base: PathBuf,
     patterns: Vec<String>,
+    exclude: Vec<String>,
     opts: MultiGlobOptions,
 }
 
@@ -56,13 +175,25 @@ impl MultiGlobBuilder {
         Self {
             base: base.as_ref().to_owned(),
             patterns: patterns.into_iter().map(|s| s.as_ref().to_owned()).collect(),
+            exclude: Vec::new(),
             opts: MultiGlobOptions::default(),
         }
     }
 
-    fn impl_build(&self, skip_invalid: bool) -> Result<MultiGlobWalker, GlobError> {
-        let mut walker = MultiGlobWalker::new(self.opts.clone());
-        let glob_groups = cluster_globs(&self.patterns);
+    fn impl_build(&self, skip_invalid: bool) -> Result<MultiGlobWalker, Error> {
+        let mut opts = self.opts.clone();
+        if !self.exclude.is_empty() {
+            opts.exclude = Some(ExcludeSet::build(&self.exclude, skip_invalid)?);
+        }
+        let mut walker = MultiGlobWalker::new(opts, self.base.clone());
+        // `cluster_globs` groups patterns by common literal-prefix base, but a
+        // pattern's position within its group is not its position in
+        // `self.patterns`; tag each pattern with its original index before
+        // clustering so `walker.add` can report the same origins a single,
+        // unclustered walk would have.
+        let indexed_patterns: Vec<(usize, String)> =
+            self.patterns.iter().cloned().enumerate().collect();
+        let glob_groups = cluster_globs(&indexed_patterns);
         for (base, patterns) in glob_groups {
             let mut base = self.base.join(base);
             let is_root = base == self.base;
@@ -75,8 +206,9 @@ impl MultiGlobBuilder {
         Ok(walker.rev())
     }
 
-    /// Construct a multiglob walker; error may occur when parsing globs.
-    pub fn build(&self) -> Result<MultiGlobWalker, GlobError> {
+    /// Construct a multiglob walker; an error may occur when parsing globs, or when
+    /// a glob-like pattern component is not valid UTF-8.
+    pub fn build(&self) -> Result<MultiGlobWalker, Error> {
         self.impl_build(false)
     }
 
@@ -85,6 +217,30 @@ impl MultiGlobBuilder {
         self.impl_build(true).unwrap()
     }
 
+    /// Exclude paths matching any of the given patterns.
+    ///
+    /// Unlike `patterns` passed to [`new`], exclusion patterns are always matched
+    /// against the *whole* path relative to the base directory (they are not
+    /// clustered or split into a path prefix and a glob remainder).
+    ///
+    /// If an excluded pattern does not contain `**` and matches a directory, that
+    /// directory's contents are not traversed at all (in addition to the directory
+    /// itself not being yielded). Patterns containing `**` only suppress the
+    /// matching entries themselves, since they may still need to recurse into
+    /// excluded directories to exclude deeper paths.
+    ///
+    /// This can be called multiple times to add more exclusion patterns.
+    ///
+    /// [`new`]: #method.new
+    pub fn exclude<P, S>(mut self, patterns: P) -> Self
+    where
+        P: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.exclude.extend(patterns.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
     /// Toggle whether the globs should be matched case insensitively or not.
     ///
     /// When this option is changed, only globs added after the change will be affected.
@@ -119,6 +275,22 @@ impl MultiGlobBuilder {
         self
     }
 
+    /// Set the minimum depth of all recursive globs (those containing `**`).
+    ///
+    /// Entries shallower than `depth` are not yielded, but their subtrees are
+    /// still traversed as usual, so matches at or below `depth` are unaffected.
+    /// The same depth-counting convention as [`max_depth`] applies: depth is
+    /// counted from the point where a recursive pattern is encountered, not
+    /// from the base directory given to `new`.
+    ///
+    /// By default, there's no min depth limit (`0`).
+    ///
+    /// [`max_depth`]: #method.max_depth
+    pub fn min_depth(mut self, depth: usize) -> Self {
+        self.opts.min_depth = depth;
+        self
+    }
+
     /// Follow symbolic links. By default, this is disabled.
     ///
     /// When `yes` is `true`, symbolic links are followed as if they were
@@ -139,6 +311,23 @@ impl MultiGlobBuilder {
         self
     }
 
+    /// Independently control whether a base path that is itself a symbolic link
+    /// (or the root of a recursive `**` pattern, were it one) is dereferenced,
+    /// separate from [`follow_links`].
+    ///
+    /// By default (when this is never called), root links are followed exactly
+    /// when the path in question is the walker's root, matching the behavior of
+    /// [`WalkDir::follow_root_links`] before this was configurable. Calling this
+    /// overrides that default for every root in the walker, regardless of
+    /// [`follow_links`].
+    ///
+    /// [`follow_links`]: #method.follow_links
+    /// [`WalkDir::follow_root_links`]: walkdir::WalkDir::follow_root_links
+    pub fn follow_root_links(mut self, yes: bool) -> Self {
+        self.opts.follow_root_links = Some(yes);
+        self
+    }
+
     /// Set the maximum number of simultaneously open file descriptors used
     /// by the iterator.
     ///
@@ -184,4 +373,191 @@ impl MultiGlobBuilder {
         self.opts.same_file_system = yes;
         self
     }
+
+    /// Restrict the kind of entries that are yielded by the resulting walker.
+    ///
+    /// By default, this is [`WalkType::All`]. Note that this only affects which
+    /// entries are yielded, not which directories are descended into, so e.g.
+    /// [`WalkType::Files`] can still be used to find files several levels below
+    /// a directory that itself does not match.
+    pub fn walk_type(mut self, walk_type: WalkType) -> Self {
+        self.opts.walk_type = walk_type;
+        self
+    }
+
+    /// Actively detect symlink loops when following links, instead of relying on
+    /// the underlying OS/`walkdir` error that surfaces once a loop has already
+    /// been re-entered.
+    ///
+    /// When enabled together with [`follow_links`], directories are checked
+    /// against the device/inode pairs of their ancestors. Within a recursive
+    /// `**` component, every directory entered is checked against the
+    /// directories above it in that walk and the followed symlinks that led to
+    /// it; the entry that closed the loop is silently dropped and its descent is
+    /// skipped, rather than being followed until `walkdir`'s own re-entry error
+    /// surfaces. Outside `**` components, only directories reached through a
+    /// symbolic link are checked, and only against the symlinked directories
+    /// followed on the way to them; a match is not descended into, but the
+    /// entry itself is still yielded.
+    ///
+    /// This has no effect unless [`follow_links`] is also enabled. It is
+    /// disabled by default.
+    ///
+    /// [`follow_links`]: #method.follow_links
+    pub fn detect_loops(mut self, yes: bool) -> Self {
+        self.opts.detect_loops = yes;
+        self
+    }
+
+    /// Set the threshold at which explicit path components (e.g. the expansion of a
+    /// brace pattern like `{a,b,c}`) have their metadata fetched across worker
+    /// threads rather than one at a time.
+    ///
+    /// When a single node has more than `n` pending paths, their `symlink_metadata`/
+    /// `metadata` calls are dispatched across a small thread pool and collected before
+    /// the iterator starts draining them; this does not change the order in which
+    /// entries are yielded. This mainly helps on networked/NFS mounts, where stat
+    /// round-trips dominate and can be parallelized.
+    ///
+    /// Defaults to `32`.
+    pub fn metadata_batch_size(mut self, n: usize) -> Self {
+        self.opts.metadata_batch_size = n;
+        self
+    }
+
+    /// Set the number of threads used when a [`MultiGlobWalker::into_par_iter`]
+    /// iterator is collected with `collect_ordered` or `collect_unordered`.
+    ///
+    /// A value of `0` (the default) lets rayon pick based on the number of
+    /// available CPUs, same as [`rayon::ThreadPoolBuilder::num_threads`] with `0`.
+    /// This has no effect on the sequential [`Iterator`] implementation or on
+    /// [`visit_parallel`].
+    ///
+    /// [`MultiGlobWalker::into_par_iter`]: crate::MultiGlobWalker::into_par_iter
+    /// [`visit_parallel`]: crate::MultiGlobWalker::visit_parallel
+    pub fn parallelism(mut self, n: usize) -> Self {
+        self.opts.parallelism = n;
+        self
+    }
+
+    /// Prune subtrees by running `predicate` against every candidate entry before
+    /// it is yielded or descended into.
+    ///
+    /// Unlike glob matching, `predicate` sees the materialized [`DirEntry`] (its
+    /// `file_type`, `metadata`, `path_is_symlink`), so entries can be pruned by
+    /// mtime, size, or symlink status -- e.g. to skip descending into symlinked
+    /// directories even when [`follow_links`] is enabled.
+    ///
+    /// Returning `false` excludes the entry from the results; if it is also a
+    /// directory, its children are neither yielded nor descended into. Outside
+    /// `**` components this saves the `read_dir` call that would otherwise be
+    /// needed to discover them; within a `**` component the rejected directory's
+    /// own listing has already been opened by `walkdir` when the predicate runs,
+    /// and only the levels below it are never read.
+    ///
+    /// This can be called multiple times; later predicates only run on entries
+    /// that passed the earlier ones.
+    ///
+    /// `predicate` must be `Fn`, not `FnMut`, since it is shared (behind an `Arc`)
+    /// across every in-flight node, including across threads when using
+    /// [`visit_parallel`] or [`into_par_iter`].
+    ///
+    /// [`follow_links`]: #method.follow_links
+    /// [`visit_parallel`]: crate::MultiGlobWalker::visit_parallel
+    /// [`into_par_iter`]: crate::MultiGlobWalker::into_par_iter
+    pub fn filter_entry<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&DirEntry) -> bool + Send + Sync + 'static,
+    {
+        let next: FilterEntryFn = Arc::new(predicate);
+        self.opts.filter_entry = Some(match self.opts.filter_entry.take() {
+            Some(prev) => Arc::new(move |entry| prev(entry) && next(entry)),
+            None => next,
+        });
+        self
+    }
+
+    /// Run `callback` once for every directory listing read during a non-recursive
+    /// glob match (i.e. a pattern component that does not contain `**`), right
+    /// after its entries are read and before any glob matching is applied to them.
+    ///
+    /// `callback` receives the depth of the listing (always `1`, since a
+    /// non-recursive component only ever looks at the direct children of its base),
+    /// the path of the directory being listed, and a mutable reference to the
+    /// just-read children. It may reorder the slice (controlling the order those
+    /// children are later matched and yielded in) or shrink it with
+    /// [`Vec::retain`]-style pruning to drop entries before they are ever matched
+    /// against a glob.
+    ///
+    /// Recursive components (those containing `**`) are not currently covered:
+    /// a single `**` walks its entire subtree through one lazily-streamed
+    /// `walkdir` pass rather than directory-by-directory, so there is no single
+    /// "this directory's listing" batch to hand to `callback` without reading
+    /// the whole subtree up front.
+    ///
+    /// Unlike jwalk, there is no threaded generic client state parameter: keeping
+    /// [`MultiGlobWalker`] non-generic matches the rest of this crate's API, and a
+    /// closure that captures its own `Arc<Mutex<_>>` (or similar) covers the same
+    /// use case.
+    ///
+    /// This can be called multiple times; later callbacks see the slice as left by
+    /// earlier ones.
+    ///
+    /// [`MultiGlobWalker`]: crate::MultiGlobWalker
+    pub fn process_read_dir<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(usize, &Path, &mut Vec<DirEntry>) + Send + Sync + 'static,
+    {
+        self.opts.process_read_dir = Some(match self.opts.process_read_dir.take() {
+            Some(prev) => Arc::new(move |depth, path, entries| {
+                prev(depth, path, entries);
+                callback(depth, path, entries);
+            }),
+            None => Arc::new(callback),
+        });
+        self
+    }
+
+    /// Sort the children of each directory with a custom comparator before they
+    /// are matched against globs and yielded, replacing the default of sorting by
+    /// file name.
+    ///
+    /// Sorting happens one directory at a time (not globally across the whole
+    /// walk), so memory use stays bounded and `max_depth` is unaffected; this
+    /// matches how [`WalkDir::sort_by`] orders siblings.
+    ///
+    /// `cmp` must be `Fn`, not `FnMut`, for the same reason as [`filter_entry`]:
+    /// it is shared across every in-flight directory listing, including across
+    /// threads.
+    ///
+    /// [`WalkDir::sort_by`]: walkdir::WalkDir::sort_by
+    /// [`filter_entry`]: #method.filter_entry
+    pub fn sort_by<F>(mut self, cmp: F) -> Self
+    where
+        F: Fn(&DirEntry, &DirEntry) -> Ordering + Send + Sync + 'static,
+    {
+        self.opts.sort = SortOrder::Custom(Arc::new(cmp));
+        self
+    }
+
+    /// Sort the children of each directory by file name before they are matched
+    /// against globs and yielded. This is the default.
+    pub fn sort_by_file_name(mut self) -> Self {
+        self.opts.sort = SortOrder::FileName;
+        self
+    }
+
+    /// Sort the children of each directory by a derived key before they are
+    /// matched against globs and yielded.
+    ///
+    /// Equivalent to [`sort_by`] comparing `key(a)` against `key(b)`.
+    ///
+    /// [`sort_by`]: #method.sort_by
+    pub fn sort_by_key<K, F>(self, key: F) -> Self
+    where
+        K: Ord,
+        F: Fn(&DirEntry) -> K + Send + Sync + 'static,
+    {
+        self.sort_by(move |a, b| key(a).cmp(&key(b)))
+    }
 }
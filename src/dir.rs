@@ -201,6 +201,15 @@ impl DirEntry {
     pub(crate) fn from_walk(entry: walkdir::DirEntry) -> Self {
         Self(DirEntryInner::Walk(entry))
     }
+
+    /// The depth at which this entry was yielded, relative to the root of its
+    /// originating `walkdir` traversal; `0` for entries not produced by one.
+    pub(crate) fn depth(&self) -> usize {
+        match &self.0 {
+            DirEntryInner::Path(_) => 0,
+            DirEntryInner::Walk(e) => e.depth(),
+        }
+    }
 }
 
 impl From<walkdir::DirEntry> for DirEntry {
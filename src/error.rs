@@ -0,0 +1,43 @@
+use std::{error, ffi::OsString, fmt};
+
+use crate::GlobError;
+
+/// The error type returned when building a [`MultiGlobWalker`].
+///
+/// [`MultiGlobWalker`]: crate::MultiGlobWalker
+#[derive(Debug)]
+pub enum Error {
+    /// A pattern failed to parse as a glob.
+    Glob(GlobError),
+    /// A glob-like path component was not valid UTF-8, which `globset` requires.
+    ///
+    /// Pure path components (containing no glob metacharacters) are not subject
+    /// to this restriction and may contain arbitrary bytes.
+    InvalidUtf8(OsString),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(err) => write!(f, "{err}"),
+            Self::InvalidUtf8(part) => {
+                write!(f, "glob pattern component {part:?} is not valid UTF-8")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Glob(err) => Some(err),
+            Self::InvalidUtf8(_) => None,
+        }
+    }
+}
+
+impl From<GlobError> for Error {
+    fn from(err: GlobError) -> Self {
+        Self::Glob(err)
+    }
+}
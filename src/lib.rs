@@ -1,6 +1,7 @@
 mod builder;
 mod cluster;
 mod dir;
+mod error;
 mod util;
 mod walk;
 
@@ -9,4 +10,9 @@ mod tests;
 
 pub use globset::Error as GlobError;
 
-pub use crate::{builder::MultiGlobBuilder, dir::DirEntry, walk::MultiGlobWalker};
+pub use crate::{
+    builder::{MultiGlobBuilder, WalkType},
+    dir::DirEntry,
+    error::Error,
+    walk::{MultiGlobWalker, ParMultiGlobWalker, TaggedMultiGlobWalker, WalkState},
+};
@@ -126,3 +126,29 @@ fn test_double_star_with_max_depth() -> Result<()> {
     );
     Ok(())
 }
+
+#[rstest]
+fn test_double_star_with_min_depth() -> Result<()> {
+    let dir = setup_dir_with_syms();
+    let base = dir.path().join("base");
+    assert_mg_eq_wd(
+        MultiGlobBuilder::new(&base, ["x/**"]).follow_links(true).min_depth(2).build().unwrap(),
+        WalkDir::new(&base.join("x")).follow_links(true).min_depth(2).follow_root_links(false),
+    );
+    Ok(())
+}
+
+#[rstest]
+fn test_double_star_at_root_follow_root_links(#[values(false, true)] yes: bool) -> Result<()> {
+    let dir = setup_dir_with_syms();
+    let base = dir.path().join("base/x/y/asym");
+    assert_mg_eq_wd(
+        MultiGlobBuilder::new(&base, ["**"])
+            .follow_links(true)
+            .follow_root_links(yes)
+            .build()
+            .unwrap(),
+        WalkDir::new(&base).follow_links(true).follow_root_links(yes),
+    );
+    Ok(())
+}
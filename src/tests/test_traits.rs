@@ -0,0 +1,18 @@
+use crate::{DirEntry, MultiGlobBuilder, MultiGlobWalker, ParMultiGlobWalker, TaggedMultiGlobWalker};
+
+fn assert_send<T: Send>() {}
+fn assert_sync<T: Sync>() {}
+
+#[test]
+fn send_sync_traits() {
+    assert_send::<MultiGlobBuilder>();
+    assert_sync::<MultiGlobBuilder>();
+    assert_send::<MultiGlobWalker>();
+    assert_sync::<MultiGlobWalker>();
+    assert_send::<TaggedMultiGlobWalker>();
+    assert_sync::<TaggedMultiGlobWalker>();
+    assert_send::<ParMultiGlobWalker>();
+    assert_sync::<ParMultiGlobWalker>();
+    assert_send::<DirEntry>();
+    assert_sync::<DirEntry>();
+}
@@ -1,9 +1,13 @@
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 use current_dir::Cwd;
 use log::debug;
 
-use crate::MultiGlobBuilder;
+use crate::{MultiGlobBuilder, WalkState, WalkType};
 
 use super::util::{Dir, RecursiveResults};
 
@@ -153,6 +157,300 @@ fn test_walk_rel() {
     assert_eq!(res.sorted_paths(), vec![PathBuf::from("./..")]);
 }
 
+#[test]
+fn test_walk_exclude() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| b.exclude(["asym"]));
+    assert_eq!(
+        res.sorted_paths(),
+        vec![p.join("base/x/d.1"), p.join("base/x/d.2"), p.join("base/x/d.3")]
+    );
+
+    let res = mg_collect_custom(p.join("base/x"), ["d.{1,2,3}"], |b| b.exclude(["d.2"]));
+    assert_eq!(res.sorted_paths(), vec![p.join("base/x/d.1"), p.join("base/x/d.3")]);
+}
+
+#[test]
+fn test_walk_exclude_prunes_descent() {
+    let dir = Dir::tmp();
+    dir.mkdirp("a/excluded/b");
+    dir.touch("a/excluded/b/c");
+    dir.touch("a/kept");
+    let p = dir.path();
+
+    let res = mg_collect_custom(p.join("a"), ["**"], |b| b.exclude(["excluded"]));
+    assert_eq!(res.sorted_paths(), vec![p.join("a"), p.join("a/kept")]);
+}
+
+#[test]
+fn test_walk_exclude_relative_to_original_base() {
+    let dir = Dir::tmp();
+    dir.mkdirp("sub/skip");
+    dir.touch("sub/skip/file");
+    dir.touch("sub/keep");
+    let p = dir.path();
+
+    // "sub/**" clusters to a base of `p/sub`, but exclude patterns are always
+    // matched relative to the *original* builder base (here `p`), so a
+    // `sub/`-prefixed exclude pattern must still take effect.
+    let res = mg_collect_custom(p, ["sub/**"], |b| b.exclude(["sub/skip"]));
+    assert_eq!(res.sorted_paths(), vec![p.join("sub"), p.join("sub/keep")]);
+}
+
+#[test]
+fn test_walk_type() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| b.walk_type(WalkType::Files));
+    assert_eq!(
+        res.sorted_paths(),
+        vec![p.join("base/x/d.1"), p.join("base/x/d.2"), p.join("base/x/d.3")]
+    );
+
+    // `asym` is a symlinked directory; with `follow_links` off (the default) its
+    // file type is reported as a symlink, not a directory, same as it's excluded
+    // from `Files` above.
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| b.walk_type(WalkType::Dirs));
+    assert_eq!(res.sorted_paths(), Vec::<PathBuf>::new());
+
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| b.walk_type(WalkType::Symlinks));
+    assert_eq!(res.sorted_paths(), vec![p.join("base/x/asym")]);
+
+    let res = mg_collect_custom(p.join("base/x"), ["**"], |b| b.walk_type(WalkType::Files));
+    assert_eq!(
+        res.sorted_paths(),
+        vec![p.join("base/x/d.1"), p.join("base/x/d.2"), p.join("base/x/d.3")]
+    );
+
+    // `""` matches the base directory itself via the self-yield shortcut
+    // (see `test_walk_path`), which must be subject to `walk_type` filtering
+    // just like any other candidate, instead of being returned unconditionally.
+    let res = mg_collect_custom(p.join("base/x"), [""], |b| b.walk_type(WalkType::Files));
+    assert_eq!(res.sorted_paths(), Vec::<PathBuf>::new());
+}
+
+#[test]
+fn test_walk_into_tagged() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    let walker =
+        MultiGlobBuilder::new(p.join("base/x"), ["*.1", "d.*"]).build().unwrap().into_tagged();
+    let mut tagged: Vec<_> = walker
+        .map(|r| {
+            let (entry, origins) = r.unwrap();
+            (entry.into_path(), origins)
+        })
+        .collect();
+    tagged.sort();
+    assert_eq!(
+        tagged,
+        vec![
+            (p.join("base/x/d.1"), vec![0, 1]),
+            (p.join("base/x/d.2"), vec![1]),
+            (p.join("base/x/d.3"), vec![1]),
+        ]
+    );
+}
+
+#[test]
+fn test_walk_into_tagged_multi_cluster() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    // "d.*" and "asym/**" land in different glob clusters (root base vs. the
+    // "asym" sub-base); origins must still reflect each pattern's position in
+    // the original, pre-clustering list, not its position within its own cluster.
+    let walker = MultiGlobBuilder::new(p.join("base/x"), ["d.*", "asym/**"])
+        .follow_links(true)
+        .build()
+        .unwrap()
+        .into_tagged();
+    let mut tagged: Vec<_> = walker
+        .map(|r| {
+            let (entry, origins) = r.unwrap();
+            (entry.into_path(), origins)
+        })
+        .collect();
+    tagged.sort();
+    assert_eq!(
+        tagged,
+        vec![
+            (p.join("base/x/asym"), vec![1]),
+            (p.join("base/x/asym/b"), vec![1]),
+            (p.join("base/x/asym/b/c"), vec![1]),
+            (p.join("base/x/asym/bsym"), vec![1]),
+            (p.join("base/x/asym/bsym/c"), vec![1]),
+            (p.join("base/x/d.1"), vec![0]),
+            (p.join("base/x/d.2"), vec![0]),
+            (p.join("base/x/d.3"), vec![0]),
+        ]
+    );
+}
+
+#[test]
+fn test_walk_visit_parallel() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    let walker = MultiGlobBuilder::new(p.join("base/x"), ["*"]).build().unwrap();
+    let found = Mutex::new(Vec::new());
+    walker.visit_parallel(4, |entry| {
+        found.lock().unwrap().push(entry.unwrap().into_path());
+        WalkState::Continue
+    });
+    let mut found = found.into_inner().unwrap();
+    found.sort();
+    assert_eq!(
+        found,
+        vec![
+            p.join("base/x/asym"),
+            p.join("base/x/d.1"),
+            p.join("base/x/d.2"),
+            p.join("base/x/d.3")
+        ]
+    );
+}
+
+#[test]
+fn test_walk_path_metadata_batch_size() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    // force the prefetch path (batch size smaller than the number of candidates)
+    // and check it still yields the same results as the single-threaded fallback.
+    let res = mg_collect_custom(p.join("base/x"), ["d.1", "d.2", "d.3", "asym", "missing"], |b| {
+        b.metadata_batch_size(1)
+    });
+    assert_eq!(
+        res.sorted_paths(),
+        vec![
+            p.join("base/x/asym"),
+            p.join("base/x/d.1"),
+            p.join("base/x/d.2"),
+            p.join("base/x/d.3")
+        ]
+    );
+}
+
+#[test]
+fn test_walk_into_par_iter() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    let walker = MultiGlobBuilder::new(p.join("base/x"), ["*"]).parallelism(2).build().unwrap();
+    let mut paths: Vec<_> = walker
+        .into_par_iter()
+        .collect_ordered()
+        .into_iter()
+        .map(|r| r.unwrap().into_path())
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            p.join("base/x/asym"),
+            p.join("base/x/d.1"),
+            p.join("base/x/d.2"),
+            p.join("base/x/d.3")
+        ]
+    );
+}
+
+#[test]
+fn test_walk_filter_entry() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    // `asym` is a symlinked directory; reject it even though `follow_links` is on,
+    // and check its target's contents (which would otherwise be discovered through
+    // it) are never yielded.
+    let res = mg_collect_custom(p.join("base/x"), ["**"], |b| {
+        b.follow_links(true).filter_entry(|entry| !entry.path_is_symlink())
+    });
+    assert_eq!(
+        res.sorted_paths(),
+        vec![
+            p.join("base/x"),
+            p.join("base/x/d.1"),
+            p.join("base/x/d.2"),
+            p.join("base/x/d.3")
+        ]
+    );
+
+    // `""` matches the base directory itself via the self-yield shortcut; the
+    // predicate must be consulted for it too, not just for candidates found by
+    // actually reading a directory.
+    let res = mg_collect_custom(p.join("base/x"), [""], |b| b.filter_entry(|_| false));
+    assert_eq!(res.sorted_paths(), Vec::<PathBuf>::new());
+}
+
+#[test]
+fn test_walk_process_read_dir() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    // drop `d.2` and reverse the rest, and check the reversal is reflected in
+    // yield order (rather than just sorting away any trace of it, as the other
+    // tests in this file do).
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| {
+        b.process_read_dir(|depth, dir, entries| {
+            assert_eq!(depth, 1);
+            assert!(dir.ends_with("base/x"));
+            entries.retain(|e| e.file_name() != OsStr::new("d.2"));
+            entries.reverse();
+        })
+    });
+    assert_eq!(
+        res.paths(),
+        vec![p.join("base/x/d.3"), p.join("base/x/d.1"), p.join("base/x/asym")]
+    );
+}
+
+#[test]
+fn test_walk_sort_by() {
+    let dir = setup_dir_with_syms();
+    let p = dir.path();
+
+    // sort_by_key, here reversing file name order
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| {
+        b.sort_by_key(|e| std::cmp::Reverse(e.file_name().to_owned()))
+    });
+    assert_eq!(
+        res.paths(),
+        vec![
+            p.join("base/x/d.3"),
+            p.join("base/x/d.2"),
+            p.join("base/x/d.1"),
+            p.join("base/x/asym")
+        ]
+    );
+
+    // sort_by with an arbitrary comparator: symlinks before everything else
+    // (the comparator sees unfollowed entries, so `asym` is a symlink here)
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| {
+        b.sort_by(|a, b| b.path_is_symlink().cmp(&a.path_is_symlink()))
+    });
+    assert_eq!(res.paths()[0], p.join("base/x/asym"));
+
+    // sort_by_file_name undoes a previously set custom comparator
+    let res = mg_collect_custom(p.join("base/x"), ["*"], |b| {
+        b.sort_by_key(|e| std::cmp::Reverse(e.file_name().to_owned())).sort_by_file_name()
+    });
+    assert_eq!(
+        res.paths(),
+        vec![
+            p.join("base/x/asym"),
+            p.join("base/x/d.1"),
+            p.join("base/x/d.2"),
+            p.join("base/x/d.3")
+        ]
+    );
+}
+
 #[test]
 fn test_walk_loop() {
     let dir = Dir::tmp();
@@ -182,3 +480,20 @@ fn test_walk_loop() {
         ]
     );
 }
+
+#[test]
+fn test_walk_loop_detect_loops() {
+    let dir = Dir::tmp();
+    dir.mkdirp("x/base/a/b");
+    dir.symlink_dir("x", "x/base/a/b/c");
+    let p = dir.path();
+
+    let res = mg_collect_custom(p.join("x/base"), ["**"], |b| {
+        b.follow_links(true).detect_loops(true)
+    });
+    res.assert_no_errors();
+    assert_eq!(
+        res.sorted_paths(),
+        vec![p.join("x/base"), p.join("x/base/a"), p.join("x/base/a/b"), p.join("x/base/a/b/c")]
+    );
+}
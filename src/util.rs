@@ -1,4 +1,4 @@
-use std::{io, path::Path};
+use std::{ffi::OsStr, io, path::Path};
 
 /// Check if a component of a path looks like it may be a glob pattern.
 ///
@@ -8,8 +8,14 @@ use std::{io, path::Path};
 /// false positives (e.g. patterns like 'foo[bar' or 'foo{bar') in which case correctness
 /// will not be affected but efficiency might be (because we'll traverse more than we should),
 /// however it should not return false negatives.
-pub fn is_glob_like(part: &str) -> bool {
-    ["*", "{", "}", "?", "[", "]"].into_iter().any(|c| part.contains(c))
+///
+/// This operates on raw bytes rather than requiring `part` to be valid UTF-8: all glob
+/// metacharacters are ASCII, so a byte-wise scan is correct even for components that
+/// aren't representable as `str` (e.g. arbitrary non-UTF-8 paths on Unix).
+pub fn is_glob_like(part: &OsStr) -> bool {
+    part.as_encoded_bytes()
+        .iter()
+        .any(|&b| matches!(b, b'*' | b'{' | b'}' | b'?' | b'[' | b']'))
 }
 
 #[cfg(unix)]
@@ -37,3 +43,26 @@ pub fn device_num<P: AsRef<Path>>(_: P) -> io::Result<u64> {
         "walkdir: same_file_system option not supported on this platform",
     ))
 }
+
+/// A cheap, platform-appropriate identity for a directory, used to detect
+/// symlink loops while following links.
+#[cfg(unix)]
+pub fn device_and_inode<P: AsRef<Path>>(path: P) -> io::Result<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = path.as_ref().metadata()?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// On platforms without a cheap inode equivalent, fall back to the canonicalized
+/// path as a proxy for identity; this is more expensive but still correct.
+#[cfg(not(unix))]
+pub fn device_and_inode<P: AsRef<Path>>(path: P) -> io::Result<(u64, u64)> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let canonical = path.as_ref().canonicalize()?;
+    let mut hasher = DefaultHasher::new();
+    canonical.hash(&mut hasher);
+    Ok((0, hasher.finish()))
+}
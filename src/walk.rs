@@ -1,15 +1,28 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ffi::{OsStr, OsString},
     fmt, fs, io, mem,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
 };
 
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use log::debug;
+use rayon::iter::{
+    plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer},
+    ParallelIterator,
+};
 use walkdir::WalkDir;
 
-use crate::{builder::MultiGlobOptions, util::is_glob_like, DirEntry, GlobError};
+use crate::{
+    builder::MultiGlobOptions,
+    util::{device_and_inode, is_glob_like},
+    DirEntry, Error,
+};
 
 macro_rules! itry {
     ($e:expr) => {
@@ -28,65 +41,83 @@ enum WalkNodeType {
     Walk,
 }
 
+/// Check if an OS path component contains a literal `**`, again scanning raw
+/// bytes so that non-UTF-8 components are handled correctly (see [`is_glob_like`]).
+fn contains_double_star(part: &OsStr) -> bool {
+    part.as_encoded_bytes().windows(2).any(|w| w == b"**")
+}
+
 #[derive(Default, Clone)]
 struct WalkPlanNode {
     node_type: WalkNodeType,
     is_terminal: bool,
-    patterns: BTreeMap<String, WalkPlanNode>,
+    /// Indices of the original input patterns (as passed to `build`) that reach
+    /// this node, i.e. that this node is a terminal match for.
+    origins: BTreeSet<usize>,
+    patterns: BTreeMap<OsString, WalkPlanNode>,
 }
 
 impl WalkPlanNode {
-    pub fn build(patterns: &[impl AsRef<str>]) -> Self {
+    pub fn build(patterns: &[impl AsRef<OsStr>]) -> Self {
+        Self::build_indexed(patterns.iter().enumerate())
+    }
+
+    /// Like [`build`], but the origin tagging each pattern is given explicitly
+    /// rather than being the pattern's position in `patterns`. Used when building
+    /// a node from a single glob-cluster's patterns, whose positions within the
+    /// cluster are not the same as their positions in the original input list.
+    ///
+    /// [`build`]: Self::build
+    pub fn build_indexed<P: AsRef<OsStr>>(patterns: impl IntoIterator<Item = (usize, P)>) -> Self {
         let mut root = Self::default();
-        for pattern in patterns {
-            let parts: Vec<_> = Path::new(pattern.as_ref())
-                .components()
-                .map(|c| c.as_os_str().to_str().unwrap())
-                .collect();
-            root.insert(&parts);
+        for (origin, pattern) in patterns {
+            let parts: Vec<_> =
+                Path::new(pattern.as_ref()).components().map(|c| c.as_os_str()).collect();
+            root.insert(&parts, origin);
         }
         root.optimize();
         root
     }
 
-    pub fn terminal() -> Self {
-        Self { is_terminal: true, ..Self::default() }
+    pub fn terminal(origin: usize) -> Self {
+        Self { is_terminal: true, origins: BTreeSet::from([origin]), ..Self::default() }
     }
 
-    pub fn insert(&mut self, parts: &[&str]) {
-        debug!("WalkPlanNode::insert({parts:?})");
+    pub fn insert(&mut self, parts: &[&OsStr], origin: usize) {
+        debug!("WalkPlanNode::insert({parts:?}, {origin})");
         let Some((&part, tail)) = parts.split_first() else {
             self.is_terminal = true;
+            self.origins.insert(origin);
             return;
         };
-        let make_path = || parts.iter().collect::<PathBuf>().to_str().unwrap().to_owned();
+        let make_path = || parts.iter().collect::<PathBuf>().into_os_string();
         if self.node_type == WalkNodeType::Walk {
-            self.patterns.insert(make_path(), Self::terminal());
+            self.patterns.insert(make_path(), Self::terminal(origin));
             return;
         }
-        let part = part.to_owned();
-        if part.contains("**") {
+        if contains_double_star(part) {
             self.node_type = WalkNodeType::Walk;
             let mut patterns = Vec::new();
             self.collect(PathBuf::new(), &mut patterns);
             assert!(self.patterns.is_empty());
-            for pattern in patterns {
-                self.patterns.insert(pattern, Self::terminal());
+            for (pattern, origins) in patterns {
+                let node = Self { is_terminal: true, origins, ..Self::default() };
+                self.patterns.insert(pattern, node);
             }
-            self.patterns.insert(make_path(), Self::terminal());
-        } else if is_glob_like(&part) {
+            self.patterns.insert(make_path(), Self::terminal(origin));
+        } else if is_glob_like(part) {
             self.node_type = WalkNodeType::Glob;
-            self.patterns.entry(part).or_default().insert(tail);
+            self.patterns.entry(part.to_owned()).or_default().insert(tail, origin);
         } else {
-            self.patterns.entry(part).or_default().insert(tail);
+            self.patterns.entry(part.to_owned()).or_default().insert(tail, origin);
         }
     }
 
-    pub fn collect(&mut self, path: PathBuf, out: &mut Vec<String>) {
+    pub fn collect(&mut self, path: PathBuf, out: &mut Vec<(OsString, BTreeSet<usize>)>) {
         for (k, mut v) in mem::take(&mut self.patterns) {
             let path = path.join(k);
             if v.is_terminal {
-                out.push(path.to_str().unwrap().to_owned());
+                out.push((path.clone().into_os_string(), v.origins.clone()));
             }
             v.collect(path, out);
         }
@@ -122,14 +153,14 @@ impl WalkPlanNode {
 impl fmt::Debug for WalkPlanNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let name = if self.patterns.is_empty() && self.is_terminal {
-            "Terminal".into()
+            format!("Terminal{:?}", self.origins)
         } else {
-            let t = if self.is_terminal { "[T]" } else { "" };
+            let t = if self.is_terminal { format!("[T]{:?}", self.origins) } else { "".into() };
             format!("{:?}{t}", self.node_type)
         };
         let mut s = f.debug_struct(&name);
         for (k, v) in &self.patterns {
-            s.field(k, &v);
+            s.field(&k.to_string_lossy(), &v);
         }
         s.finish()
     }
@@ -137,7 +168,7 @@ impl fmt::Debug for WalkPlanNode {
 
 #[derive(Clone)]
 enum WalkNodeMatcher {
-    Path { paths: Vec<String> },
+    Path { paths: Vec<OsString> },
     Walk { globset: GlobSet, recursive: bool },
 }
 
@@ -145,23 +176,33 @@ enum WalkNodeMatcher {
 struct WalkPlanNodeCompiled {
     matcher: WalkNodeMatcher,
     is_terminal: bool,
+    /// Indices of the original input patterns that this node is a terminal match
+    /// for (empty when `!is_terminal`).
+    origins: Vec<usize>,
     destinations: Vec<WalkPlanNodeCompiled>,
 }
 
 impl WalkPlanNodeCompiled {
-    pub fn new(node: &WalkPlanNode, skip_invalid: bool) -> Result<Self, GlobError> {
+    pub fn new(node: &WalkPlanNode, skip_invalid: bool) -> Result<Self, Error> {
         // TODO: when skip_invalid is enabled, it could return a list of globs that failed and errors
         let mut destinations = Vec::new();
         let matcher = if node.node_type == WalkNodeType::Path {
+            // pure-path components never need to be valid UTF-8: they are joined onto
+            // the base path directly and never passed to globset.
             destinations.extend(node.patterns.values().cloned());
             WalkNodeMatcher::Path { paths: node.patterns.keys().cloned().collect() }
         } else {
             let mut globset = GlobSetBuilder::new();
             for (k, v) in &node.patterns {
+                let k = match k.to_str() {
+                    Some(k) => k,
+                    None if skip_invalid => continue,
+                    None => return Err(Error::InvalidUtf8(k.clone())),
+                };
                 let glob = match Glob::new(k) {
                     Ok(glob) => glob,
                     Err(_) if skip_invalid => continue,
-                    Err(err) => return Err(err),
+                    Err(err) => return Err(err.into()),
                 };
                 globset.add(glob);
                 destinations.push(v.clone());
@@ -172,23 +213,24 @@ impl WalkPlanNodeCompiled {
                     destinations.clear();
                     GlobSet::empty()
                 }
-                Err(err) => return Err(err),
+                Err(err) => return Err(err.into()),
             };
             let recursive = node.node_type == WalkNodeType::Walk;
             WalkNodeMatcher::Walk { globset, recursive }
         };
         let destinations =
             destinations.iter().map(|d| Self::new(d, skip_invalid)).collect::<Result<_, _>>()?;
-        Ok(Self { matcher, is_terminal: node.is_terminal, destinations })
+        let origins = node.origins.iter().copied().collect();
+        Ok(Self { matcher, is_terminal: node.is_terminal, origins, destinations })
     }
 }
 
 impl fmt::Debug for WalkPlanNodeCompiled {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let name = if self.destinations.is_empty() && self.is_terminal {
-            "Terminal".into()
+            format!("Terminal{:?}", self.origins)
         } else {
-            let t = if self.is_terminal { "[T]" } else { "" };
+            let t = if self.is_terminal { format!("[T]{:?}", self.origins) } else { "".into() };
             let n =
                 if matches!(self.matcher, WalkNodeMatcher::Path { .. }) { "Path" } else { "Glob" };
             format!("{n}{t}")
@@ -212,61 +254,172 @@ impl fmt::Debug for WalkPlanNodeCompiled {
 }
 
 enum NodeWalkerState {
-    Path { paths: Vec<PathBuf>, index: usize },
-    Walk { globset: GlobSet, walker: walkdir::IntoIter, base_checked: bool },
+    Path {
+        paths: Vec<PathBuf>,
+        index: usize,
+        /// Metadata fetched ahead of time across worker threads, one slot per path
+        /// in `paths`, when `paths.len()` exceeded `metadata_batch_size` at
+        /// construction time. Each slot is taken exactly once, as `index` reaches it.
+        prefetched: Option<Vec<Option<StatResult>>>,
+    },
+    // boxed: this variant is considerably larger than `Path` (a `walkdir::IntoIter`
+    // plus a `GlobSet` alone dwarf it), and every `NodeWalker` carries one `state`
+    // regardless of which variant is active.
+    Walk(Box<WalkData>),
+}
+
+struct WalkData {
+    globset: GlobSet,
+    walker: walkdir::IntoIter,
+    base_checked: bool,
+    /// Whether this node's pattern contains `**`. Non-recursive nodes only ever
+    /// walk a single directory (capped to `max_depth(1)`), so their listing can
+    /// be read eagerly as one batch for [`MultiGlobOptions::process_read_dir`];
+    /// recursive nodes stream an entire subtree through one `walkdir` instance
+    /// and have no single listing to batch.
+    recursive: bool,
+    /// The batched, [`process_read_dir`]-adjusted listing of a non-recursive
+    /// node's one directory, and the next index to yield from it. Always
+    /// `None` for recursive nodes, and for non-recursive ones with no
+    /// [`process_read_dir`] callback registered (those stream entries one at
+    /// a time instead, without buffering the whole directory); populated
+    /// lazily on first access otherwise.
+    ///
+    /// [`process_read_dir`]: crate::MultiGlobBuilder::process_read_dir
+    batched: Option<(Vec<DirEntry>, usize)>,
+    /// Device/inode pairs of followed-symlink-or-plain directories entered
+    /// so far by `walker` itself, paired with the depth they were entered
+    /// at. Unlike [`NodeWalker::ancestors`], this tracks descent *within* a
+    /// single recursive `walkdir` instance, since a `**` node never spawns
+    /// per-directory child `NodeWalker`s for `ancestors` to be threaded
+    /// through. Popped back to the current entry's depth on every step, so
+    /// it always holds exactly the true ancestor chain. Only populated when
+    /// `recursive` and [`MultiGlobOptions::detect_loops`] are both set.
+    loop_stack: Vec<(usize, u64, u64)>,
 }
 
 type WalkDirFn = Arc<dyn Fn(WalkDir) -> WalkDir + Send + Sync + 'static>;
 
+/// The result of statting a single path: its metadata, and whether that metadata
+/// was fetched by following a symlink (i.e. `follow_links` was set and the path
+/// turned out to be one).
+type StatResult = io::Result<(fs::Metadata, bool)>;
+
+/// Fetch `symlink_metadata` for `path`, and then `metadata` in its place if it turns
+/// out to be a symlink and `follow_links` is set. Mirrors the single-threaded
+/// fallback performed inline in [`NodeWalker::next`].
+fn stat_path(path: &Path, follow_links: bool) -> StatResult {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_symlink() && follow_links {
+        Ok((fs::metadata(path)?, true))
+    } else {
+        Ok((meta, false))
+    }
+}
+
+/// Stat a batch of paths across a small number of worker threads, preserving order.
+fn prefetch_metadata(paths: &[PathBuf], follow_links: bool) -> Vec<Option<StatResult>> {
+    let num_threads = thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    let chunk_size = paths.len().div_ceil(num_threads.max(1));
+    let mut results: Vec<Option<StatResult>> = Vec::with_capacity(paths.len());
+    results.resize_with(paths.len(), || None);
+    thread::scope(|scope| {
+        for (out_chunk, path_chunk) in results.chunks_mut(chunk_size).zip(paths.chunks(chunk_size)) {
+            scope.spawn(move || {
+                for (out, path) in out_chunk.iter_mut().zip(path_chunk) {
+                    *out = Some(stat_path(path, follow_links));
+                }
+            });
+        }
+    });
+    results
+}
+
 #[derive(Default)]
 struct NodeWalkerOutput {
-    terminal: Option<DirEntry>,
+    terminal: Option<(DirEntry, Vec<usize>)>,
     nodes: Vec<NodeWalker>,
 }
 
 struct NodeWalker {
     base: PathBuf,
+    /// The original [`MultiGlobBuilder`] base directory, shared across every
+    /// cluster's `NodeWalker` tree; [`MultiGlobOptions::exclude`] patterns are
+    /// matched against paths relative to this, not to `base`.
+    ///
+    /// [`MultiGlobBuilder`]: crate::MultiGlobBuilder
+    root: Arc<Path>,
     state: NodeWalkerState,
     destinations: Vec<WalkPlanNodeCompiled>,
     index_buf: Vec<usize>,
+    exclude_buf: Vec<usize>,
     walkdir_fn: WalkDirFn,
     opts: MultiGlobOptions,
     yield_self: bool,
+    yield_self_origins: Vec<usize>,
+    /// Device/inode pairs of followed-symlink ancestor directories, used for
+    /// loop detection. Shared via `Arc` and only cloned when descending into
+    /// another followed symlink, so sibling branches never see each other's
+    /// entries.
+    ancestors: Arc<Vec<(u64, u64)>>,
+}
+
+/// The parts of a [`NodeWalker::new`] call that are carried along unchanged (or
+/// near-unchanged) from its caller to every child spawned from it, grouped to
+/// keep the constructor itself to a reasonable number of arguments.
+struct NodeWalkerParams {
+    root: Arc<Path>,
+    ancestors: Arc<Vec<(u64, u64)>>,
+    is_root: bool,
+    walkdir_fn: WalkDirFn,
+    opts: MultiGlobOptions,
+    starting_node: bool,
 }
 
 impl NodeWalker {
-    pub fn new(
-        node: WalkPlanNodeCompiled,
-        base: PathBuf,
-        is_root: bool,
-        walkdir_fn: WalkDirFn,
-        opts: MultiGlobOptions,
-        starting_node: bool,
-    ) -> Self {
+    pub fn new(node: WalkPlanNodeCompiled, base: PathBuf, params: NodeWalkerParams) -> Self {
+        let NodeWalkerParams { root, ancestors, is_root, walkdir_fn, opts, starting_node } =
+            params;
+        let origins = node.origins.clone();
         let state = match node.matcher {
             WalkNodeMatcher::Path { paths } => {
                 debug!("creating new path node at {} with paths {paths:?}", base.display());
-                let paths = paths.iter().map(|p| base.join(p)).collect();
-                NodeWalkerState::Path { paths, index: 0 }
+                let paths: Vec<PathBuf> = paths.iter().map(|p| base.join(p)).collect();
+                let prefetched = (paths.len() > opts.metadata_batch_size)
+                    .then(|| prefetch_metadata(&paths, opts.follow_links));
+                NodeWalkerState::Path { paths, index: 0, prefetched }
             }
             WalkNodeMatcher::Walk { globset, recursive } => {
                 let max_depth = if recursive { opts.max_depth } else { 1 };
+                let follow_root_links = opts.follow_root_links.unwrap_or(is_root);
                 debug!("creating new walker at {}, recursive={recursive}", base.display());
                 let walker = walkdir_fn(WalkDir::new(&base))
                     .max_depth(max_depth)
-                    .follow_root_links(is_root)
+                    .min_depth(if recursive { opts.min_depth } else { 0 })
+                    .follow_root_links(follow_root_links)
                     .into_iter();
-                NodeWalkerState::Walk { globset, walker, base_checked: !starting_node }
+                NodeWalkerState::Walk(Box::new(WalkData {
+                    globset,
+                    walker,
+                    base_checked: !starting_node,
+                    recursive,
+                    batched: None,
+                    loop_stack: Vec::new(),
+                }))
             }
         };
         Self {
             base,
+            root,
             state,
             destinations: node.destinations,
             index_buf: Vec::new(),
+            exclude_buf: Vec::new(),
             walkdir_fn,
             opts,
             yield_self: starting_node && node.is_terminal,
+            yield_self_origins: origins,
+            ancestors,
         }
     }
 }
@@ -277,6 +430,7 @@ impl Iterator for NodeWalker {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let mut entry = None;
+            let mut is_self_yield = false;
             self.index_buf.clear();
 
             match &mut self.state {
@@ -291,36 +445,35 @@ impl Iterator for NodeWalker {
                     else {
                         continue;
                     };
-                    let entry = DirEntry::from_meta(self.base.clone(), meta, follow);
-                    return Some(Ok(NodeWalkerOutput {
-                        terminal: Some(entry),
-                        ..Default::default()
-                    }));
+                    // fall through to the same exclude/walk_type/filter_entry
+                    // checks applied to every other candidate, instead of
+                    // yielding this entry unconditionally.
+                    is_self_yield = true;
+                    entry = Some(DirEntry::from_meta(self.base.clone(), meta, follow));
                 }
-                NodeWalkerState::Path { paths, index } => {
+                NodeWalkerState::Path { paths, index, prefetched } => {
                     if *index >= paths.len() {
                         return None;
                     }
                     let i = *index;
                     *index += 1;
                     let path = paths[i].clone();
-                    let Ok(mut meta) = fs::symlink_metadata(&path) else {
-                        debug!("fs::symlink_metadata error for {}, skip", path.display());
+                    let stat_result = match prefetched {
+                        Some(results) => {
+                            results[i].take().expect("each prefetched slot is consumed once")
+                        }
+                        None => stat_path(&path, self.opts.follow_links),
+                    };
+                    let Ok((meta, follow)) = stat_result else {
+                        debug!("stat error for {}, skip", path.display());
                         continue;
                     };
-                    let follow = meta.is_symlink() && self.opts.follow_links;
-                    if follow {
-                        if let Ok(m) = fs::metadata(&path) {
-                            meta = m;
-                        } else {
-                            debug!("fs::metadata error for {}, skip", path.display());
-                            continue;
-                        }
-                    }
                     entry = Some(DirEntry::from_meta(path, meta, follow));
                     self.index_buf.push(i);
                 }
-                NodeWalkerState::Walk { walker, globset, base_checked } => {
+                NodeWalkerState::Walk(walk) => {
+                    let WalkData { walker, globset, base_checked, recursive, batched, .. } =
+                        &mut **walk;
                     debug!("base_checked={base_checked}");
                     if !*base_checked {
                         // if we don't do this before kicking off walkdir iteration, it will yield an error
@@ -331,14 +484,54 @@ impl Iterator for NodeWalker {
                         }
                         *base_checked = true;
                     }
-                    debug!("trying to walk...");
-                    let walk_entry = itry!(walker.next()?);
-                    debug!("walk entry candidate: {walk_entry:?}");
-                    if let Ok(path) = walk_entry.path().strip_prefix(&self.base) {
-                        globset.matches_into(path, &mut self.index_buf);
-                        if !self.index_buf.is_empty() {
-                            entry = Some(DirEntry::from_walk(walk_entry));
+                    let candidate = if *recursive {
+                        debug!("trying to walk...");
+                        let walk_entry = itry!(walker.next()?);
+                        debug!("walk entry candidate: {walk_entry:?}");
+                        DirEntry::from_walk(walk_entry)
+                    } else if let Some(callback) = &self.opts.process_read_dir {
+                        // non-recursive with a callback registered: the whole
+                        // (single, depth-capped) directory listing has to be read
+                        // eagerly so the callback can see it as one batch, same as
+                        // it would straight off `read_dir`.
+                        if batched.is_none() {
+                            let mut entries = Vec::new();
+                            loop {
+                                match walker.next() {
+                                    None => break,
+                                    Some(Err(err)) => return Some(Err(err.into())),
+                                    Some(Ok(e)) if e.depth() == 0 => continue, // the base itself
+                                    Some(Ok(e)) => entries.push(DirEntry::from_walk(e)),
+                                }
+                            }
+                            callback(1, &self.base, &mut entries);
+                            *batched = Some((entries, 0));
+                        }
+                        let (entries, i) = batched.as_mut().unwrap();
+                        if *i >= entries.len() {
+                            return None;
                         }
+                        let candidate = entries[*i].clone();
+                        *i += 1;
+                        candidate
+                    } else {
+                        // non-recursive, no callback: stream the listing one entry
+                        // at a time instead of buffering it all upfront.
+                        loop {
+                            match walker.next() {
+                                None => return None,
+                                Some(Err(err)) => return Some(Err(err.into())),
+                                Some(Ok(e)) if e.depth() == 0 => continue, // the base itself
+                                Some(Ok(e)) => break DirEntry::from_walk(e),
+                            }
+                        }
+                    };
+                    if let Ok(path) = candidate.path().strip_prefix(&self.base) {
+                        globset.matches_into(path, &mut self.index_buf);
+                        // kept even when nothing matched, so that the exclude,
+                        // filter_entry and loop checks below also see (and can
+                        // prune) directories that only lead to deeper matches
+                        entry = Some(candidate);
                     }
                 }
             }
@@ -349,23 +542,147 @@ impl Iterator for NodeWalker {
             let path = entry.path().to_path_buf();
             let is_dir = entry.file_type().is_dir(); // will account for follow_links
 
+            let mut excluded = false;
+            let mut prune_descent = false;
+            if let Some(exclude) = &self.opts.exclude {
+                self.exclude_buf.clear();
+                if let Ok(rel) = path.strip_prefix(self.root.as_ref()) {
+                    exclude.globset.matches_into(rel, &mut self.exclude_buf);
+                }
+                if !self.exclude_buf.is_empty() {
+                    excluded = true;
+                    prune_descent =
+                        is_dir && self.exclude_buf.iter().any(|&i| !exclude.recursive[i]);
+                }
+            }
+            if prune_descent {
+                debug!("excluded, pruning descent: {}", path.display());
+                // the root's own walkdir iterator, if any, hasn't yielded
+                // anything yet at this point, so there's no current directory
+                // of its to skip (nor any sense in which the root's own
+                // children should be pruned because the root itself matched
+                // an exclude pattern).
+                if !is_self_yield {
+                    if let NodeWalkerState::Walk(walk) = &mut self.state {
+                        walk.walker.skip_current_dir();
+                    }
+                }
+                continue;
+            }
+
+            let wrong_type = !self.opts.walk_type.matches(&entry);
+
+            if self.opts.filter_entry.as_ref().is_some_and(|predicate| !predicate(&entry)) {
+                debug!("filter_entry rejected {}, pruning descent", path.display());
+                if is_dir && !is_self_yield {
+                    if let NodeWalkerState::Walk(walk) = &mut self.state {
+                        walk.walker.skip_current_dir();
+                    }
+                }
+                continue;
+            }
+
+            let mut child_ancestors = self.ancestors.clone();
+            let mut skip_descent = false;
+            // Set only for a recursive `**` node re-entering an ancestor directory
+            // (see below): such an entry is the loop closing on itself, not a
+            // genuine result, so it must be dropped entirely rather than merely
+            // having its descent blocked like an ordinary followed-symlink dir.
+            let mut loop_excluded = false;
+            if self.opts.detect_loops && self.opts.follow_links && !is_self_yield {
+                match &mut self.state {
+                    NodeWalkerState::Walk(walk) if walk.recursive => {
+                        // a recursive `**` node drives its whole subtree through one
+                        // `walkdir::IntoIter` instead of spawning a child `NodeWalker`
+                        // per directory, so there's no `child_ancestors` hand-off to
+                        // rely on; track the descent stack here instead, popped back
+                        // to the current entry's depth on every step. Every entered
+                        // directory is tracked (not just symlinks), since the symlink
+                        // that closes a loop may point back at an ordinary ancestor
+                        // directory rather than at another symlink: the loop is only
+                        // observable once its listing reproduces an already-visited
+                        // directory, one or more levels below the symlink itself.
+                        let depth = entry.depth();
+                        let loop_stack = &mut walk.loop_stack;
+                        while loop_stack.last().is_some_and(|&(d, ..)| d >= depth) {
+                            loop_stack.pop();
+                        }
+                        if is_dir {
+                            if let Ok(id) = device_and_inode(&path) {
+                                let is_loop = self.ancestors.contains(&id)
+                                    || loop_stack.iter().any(|&(_, dev, ino)| (dev, ino) == id);
+                                if is_loop {
+                                    debug!("loop detected at {}, skipping descent", path.display());
+                                    skip_descent = true;
+                                    loop_excluded = true;
+                                } else {
+                                    loop_stack.push((depth, id.0, id.1));
+                                }
+                            }
+                        }
+                    }
+                    _ if is_dir && entry.path_is_symlink() => {
+                        if let Ok(id) = device_and_inode(&path) {
+                            if self.ancestors.contains(&id) {
+                                debug!("loop detected at {}, skipping descent", path.display());
+                                skip_descent = true;
+                            } else {
+                                let mut ancestors = (*child_ancestors).clone();
+                                ancestors.push(id);
+                                child_ancestors = Arc::new(ancestors);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if skip_descent {
+                if let NodeWalkerState::Walk(walk) = &mut self.state {
+                    walk.walker.skip_current_dir();
+                }
+            }
+
             let mut entry = Some(entry);
-            for &i in &self.index_buf {
-                let dst = &self.destinations[i];
-                if dst.is_terminal && out.terminal.is_none() {
-                    out.terminal = entry.take();
+            let mut terminal_origins: Vec<usize> = Vec::new();
+            if is_self_yield {
+                // the self-yield terminal has no `destinations` of its own to
+                // spawn children from (the node's real `Path`/`Walk` state
+                // handles that on subsequent calls); it only needs the same
+                // exclude/walk_type/filter_entry checks applied above (loop
+                // detection is not applied to it).
+                if !excluded && !wrong_type && !loop_excluded {
+                    terminal_origins.extend_from_slice(&self.yield_self_origins);
                 }
-                if !dst.destinations.is_empty() && is_dir {
-                    out.nodes.push(NodeWalker::new(
-                        dst.clone(),
-                        path.clone(),
-                        false,
-                        self.walkdir_fn.clone(),
-                        self.opts.clone(),
-                        false,
-                    ));
+            } else {
+                for &i in &self.index_buf {
+                    let dst = &self.destinations[i];
+                    if dst.is_terminal && !excluded && !wrong_type && !loop_excluded {
+                        terminal_origins.extend_from_slice(&dst.origins);
+                    }
+                    if !dst.destinations.is_empty() && is_dir && !skip_descent {
+                        out.nodes.push(NodeWalker::new(
+                            dst.clone(),
+                            path.clone(),
+                            NodeWalkerParams {
+                                root: self.root.clone(),
+                                ancestors: child_ancestors.clone(),
+                                is_root: false,
+                                walkdir_fn: self.walkdir_fn.clone(),
+                                opts: self.opts.clone(),
+                                starting_node: false,
+                            },
+                        ));
+                    }
                 }
             }
+            if !terminal_origins.is_empty() {
+                // a path can match more than one input pattern at once (e.g. `*.1`
+                // and `d.*` both matching `d.1`); tag it with every origin, not just
+                // the first one encountered.
+                terminal_origins.sort_unstable();
+                terminal_origins.dedup();
+                out.terminal = entry.take().map(|e| (e, terminal_origins));
+            }
             debug!("out.terminal={:?}", out.terminal);
             if out.terminal.is_some() || !out.nodes.is_empty() {
                 return Some(Ok(out));
@@ -382,28 +699,51 @@ impl Iterator for NodeWalker {
 /// [`MultiGlobBuilder`]: struct.MultiGlobBuilder.html
 pub struct MultiGlobWalker {
     opts: MultiGlobOptions,
+    /// The original base directory passed to [`MultiGlobBuilder::new`], i.e.
+    /// before clustering splits it into a per-group base and a pattern
+    /// remainder. Shared by every [`NodeWalker`] regardless of which cluster it
+    /// belongs to, since [`MultiGlobOptions::exclude`] patterns are documented
+    /// as relative to it, not to any individual cluster's base.
+    ///
+    /// [`MultiGlobBuilder::new`]: crate::MultiGlobBuilder::new
+    base: Arc<Path>,
     stack: Vec<NodeWalker>,
 }
 
 impl MultiGlobWalker {
-    pub(crate) fn new(opts: MultiGlobOptions) -> Self {
-        Self { opts, stack: Vec::new() }
+    pub(crate) fn new(opts: MultiGlobOptions, base: PathBuf) -> Self {
+        Self { opts, base: Arc::from(base.as_path()), stack: Vec::new() }
     }
 
     pub(crate) fn add(
         &mut self,
         base: PathBuf,
         is_root: bool,
-        patterns: Vec<String>,
+        // paired with the pattern's index in the original, pre-clustering input
+        // list, so that entries yielded from this cluster are tagged with the
+        // same origins a single, unclustered walk would have produced.
+        patterns: Vec<(usize, String)>,
         skip_invalid: bool,
-    ) -> Result<(), GlobError> {
+    ) -> Result<(), Error> {
         debug!(base:?, is_root, patterns:?; "MultiGlobWalker::add()");
-        let plan = WalkPlanNode::build(&patterns);
+        let plan = WalkPlanNode::build_indexed(patterns);
         debug!(plan:?; "walk plan node");
         let node = WalkPlanNodeCompiled::new(&plan, skip_invalid)?;
         let opts = self.opts.clone();
         let walkdir_fn = Arc::new(move |walkdir| opts.configure_walkdir(walkdir));
-        let walker = NodeWalker::new(node, base, is_root, walkdir_fn, self.opts.clone(), true);
+        let root = self.base.clone();
+        let walker = NodeWalker::new(
+            node,
+            base,
+            NodeWalkerParams {
+                root,
+                ancestors: Arc::new(Vec::new()),
+                is_root,
+                walkdir_fn,
+                opts: self.opts.clone(),
+                starting_node: true,
+            },
+        );
         self.stack.push(walker);
         Ok(())
     }
@@ -411,6 +751,180 @@ impl MultiGlobWalker {
     pub(crate) fn rev(self) -> Self {
         Self { stack: self.stack.into_iter().rev().collect(), ..self }
     }
+
+    /// Convert this walker into one that also yields, for each entry, the indices
+    /// of every original input pattern (in the order passed to [`MultiGlobBuilder::new`])
+    /// that matched it.
+    ///
+    /// For example, walking with patterns `["src/*.rs", "src/**"]` and matching
+    /// `src/main.rs` tags that entry with `[0, 1]`.
+    ///
+    /// [`MultiGlobBuilder::new`]: crate::MultiGlobBuilder::new
+    pub fn into_tagged(self) -> TaggedMultiGlobWalker {
+        TaggedMultiGlobWalker(self)
+    }
+
+    /// Convert this walker into a [`rayon`] parallel iterator.
+    ///
+    /// The granularity of parallelism is one top-level `NodeWalker` per glob
+    /// group (as held on the internal walk stack) at the time this is called:
+    /// each one is driven to completion, `**` expansion and all, by whichever
+    /// thread claims it, the same way a single stack slot would be drained by
+    /// [`MultiGlobWalker`]'s sequential [`Iterator`] implementation. A single
+    /// group's `**` subtree is therefore walked entirely on one thread and is
+    /// not further split across workers; parallelism only helps when there is
+    /// more than one group to distribute. Glob semantics (matching,
+    /// `follow_links`, `max_depth`) are unaffected by running in parallel
+    /// either way. The number of threads used by
+    /// [`ParMultiGlobWalker::collect_ordered`] and
+    /// [`ParMultiGlobWalker::collect_unordered`] is controlled by
+    /// [`MultiGlobBuilder::parallelism`]; other [`ParallelIterator`] methods
+    /// run on the current rayon thread pool.
+    ///
+    /// Entries are produced in an unspecified order; use [`ParMultiGlobWalker::collect_ordered`]
+    /// if you need a deterministic order, or [`ParMultiGlobWalker::collect_unordered`]
+    /// for the unordered mode.
+    ///
+    /// [`MultiGlobBuilder::parallelism`]: crate::MultiGlobBuilder::parallelism
+    pub fn into_par_iter(self) -> ParMultiGlobWalker {
+        ParMultiGlobWalker { stack: self.stack, parallelism: self.opts.parallelism }
+    }
+
+    /// Traverse the walk plan across a fixed pool of threads, invoking `visitor`
+    /// for every entry as it is produced.
+    ///
+    /// Unlike the sequential [`Iterator`] implementation, entries are handed to
+    /// `visitor` in an unspecified order: there is no guarantee that entries from
+    /// one glob group are visited before another, or that a directory's children
+    /// are visited in any particular order relative to its siblings.
+    ///
+    /// `visitor` returns a [`WalkState`] which controls how the walk proceeds:
+    /// [`WalkState::Continue`] keeps going as normal, [`WalkState::Skip`] drops
+    /// the child walks spawned for the entry just visited (without affecting other
+    /// in-flight work), and [`WalkState::Quit`] asks every worker thread to stop as
+    /// soon as possible. Inside a `**` component the whole subtree is walked by one
+    /// recursive traversal rather than by child walks, so there `Skip` does not
+    /// stop descent into the entry. Returned for an error, `Skip` abandons the
+    /// rest of the walk that produced the error.
+    ///
+    /// `num_threads` is clamped to be at least `1`.
+    pub fn visit_parallel<F>(self, num_threads: usize, visitor: F)
+    where
+        F: FnMut(io::Result<DirEntry>) -> WalkState + Send,
+    {
+        let num_threads = num_threads.max(1);
+        let pending = AtomicUsize::new(self.stack.len());
+        let shared = ParallelShared {
+            queue: Mutex::new(self.stack.into_iter().collect()),
+            pending,
+            cv: Condvar::new(),
+            quit: AtomicBool::new(false),
+        };
+        let visitor = Mutex::new(visitor);
+        thread::scope(|scope| {
+            for _ in 0..num_threads {
+                let shared = &shared;
+                let visitor = &visitor;
+                scope.spawn(move || parallel_worker(shared, visitor));
+            }
+        });
+    }
+}
+
+/// Controls how [`MultiGlobWalker::visit_parallel`] proceeds after visiting an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkState {
+    /// Keep walking as normal.
+    Continue,
+    /// Drop the child walks spawned for the entry just visited (if it is a
+    /// directory). Within a `**` component its subtree is still walked.
+    Skip,
+    /// Stop the walk across all worker threads as soon as possible.
+    Quit,
+}
+
+struct ParallelShared {
+    queue: Mutex<VecDeque<NodeWalker>>,
+    /// Number of `NodeWalker`s that are either queued or currently being driven
+    /// by a worker. Reaching zero means the walk is complete.
+    pending: AtomicUsize,
+    cv: Condvar,
+    quit: AtomicBool,
+}
+
+impl ParallelShared {
+    fn take_work(&self) -> Option<NodeWalker> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.quit.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(node) = queue.pop_front() {
+                return Some(node);
+            }
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                return None;
+            }
+            queue = self.cv.wait(queue).unwrap();
+        }
+    }
+
+    fn push_work(&self, nodes: Vec<NodeWalker>) {
+        if nodes.is_empty() {
+            return;
+        }
+        self.pending.fetch_add(nodes.len(), Ordering::SeqCst);
+        self.queue.lock().unwrap().extend(nodes);
+        self.cv.notify_all();
+    }
+
+    fn finish_one(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        self.cv.notify_all();
+    }
+
+    fn request_quit(&self) {
+        self.quit.store(true, Ordering::SeqCst);
+        self.cv.notify_all();
+    }
+}
+
+fn parallel_worker<F>(shared: &ParallelShared, visitor: &Mutex<F>)
+where
+    F: FnMut(io::Result<DirEntry>) -> WalkState + Send,
+{
+    while let Some(mut node) = shared.take_work() {
+        loop {
+            match node.next() {
+                None => break,
+                Some(Err(err)) => match (visitor.lock().unwrap())(Err(err)) {
+                    WalkState::Continue => continue,
+                    WalkState::Skip => break,
+                    WalkState::Quit => {
+                        shared.request_quit();
+                        return;
+                    }
+                },
+                Some(Ok(mut out)) => {
+                    let mut keep_children = true;
+                    if let Some((terminal, _origins)) = out.terminal {
+                        match (visitor.lock().unwrap())(Ok(terminal)) {
+                            WalkState::Continue => {}
+                            WalkState::Skip => keep_children = false,
+                            WalkState::Quit => {
+                                shared.request_quit();
+                                return;
+                            }
+                        }
+                    }
+                    if keep_children {
+                        shared.push_work(mem::take(&mut out.nodes));
+                    }
+                }
+            }
+        }
+        shared.finish_one();
+    }
 }
 
 impl Iterator for MultiGlobWalker {
@@ -423,6 +937,32 @@ impl Iterator for MultiGlobWalker {
                 Some(Err(err)) => return Some(Err(err)),
                 Some(Ok(mut res)) => {
                     self.stack.append(&mut res.nodes);
+                    if let Some((terminal, _origins)) = res.terminal {
+                        return Some(Ok(terminal));
+                    }
+                }
+            };
+        }
+        None
+    }
+}
+
+/// An iterator like [`MultiGlobWalker`], but that also yields the indices of
+/// every original input pattern that matched each entry.
+///
+/// Constructed with [`MultiGlobWalker::into_tagged`].
+pub struct TaggedMultiGlobWalker(MultiGlobWalker);
+
+impl Iterator for TaggedMultiGlobWalker {
+    type Item = io::Result<(DirEntry, Vec<usize>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.0.stack.is_empty() {
+            match self.0.stack.last_mut().unwrap().next() {
+                None => _ = self.0.stack.pop(),
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(mut res)) => {
+                    self.0.stack.append(&mut res.nodes);
                     if let Some(terminal) = res.terminal {
                         return Some(Ok(terminal));
                     }
@@ -433,6 +973,118 @@ impl Iterator for MultiGlobWalker {
     }
 }
 
+/// A `rayon` [`ParallelIterator`] over a [`MultiGlobWalker`]'s entries.
+///
+/// Constructed with [`MultiGlobWalker::into_par_iter`].
+pub struct ParMultiGlobWalker {
+    stack: Vec<NodeWalker>,
+    parallelism: usize,
+}
+
+impl ParMultiGlobWalker {
+    /// Collect all entries, buffering them and sorting by path so the result is
+    /// deterministic regardless of how work was scheduled across threads.
+    ///
+    /// Errors sort before all successfully yielded entries, and are otherwise
+    /// left in whatever relative order they were produced in.
+    pub fn collect_ordered(self) -> Vec<io::Result<DirEntry>> {
+        let mut entries = self.collect_with_parallelism();
+        entries.sort_by(|a, b| match (a, b) {
+            (Ok(a), Ok(b)) => a.path().cmp(b.path()),
+            (Err(_), Ok(_)) => std::cmp::Ordering::Less,
+            (Ok(_), Err(_)) => std::cmp::Ordering::Greater,
+            (Err(_), Err(_)) => std::cmp::Ordering::Equal,
+        });
+        entries
+    }
+
+    /// Collect all entries in whatever order they are produced in, without
+    /// buffering for a deterministic sort. Equivalent to `self.collect()`, except
+    /// that it runs on a thread pool sized by [`MultiGlobBuilder::parallelism`]
+    /// when that is non-zero.
+    ///
+    /// [`MultiGlobBuilder::parallelism`]: crate::MultiGlobBuilder::parallelism
+    pub fn collect_unordered(self) -> Vec<io::Result<DirEntry>> {
+        self.collect_with_parallelism()
+    }
+
+    fn collect_with_parallelism(self) -> Vec<io::Result<DirEntry>> {
+        let parallelism = self.parallelism;
+        if parallelism == 0 {
+            return self.collect();
+        }
+        match rayon::ThreadPoolBuilder::new().num_threads(parallelism).build() {
+            Ok(pool) => pool.install(|| self.collect()),
+            Err(_) => self.collect(),
+        }
+    }
+}
+
+impl ParallelIterator for ParMultiGlobWalker {
+    type Item = io::Result<DirEntry>;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        bridge_unindexed(NodeWalkerProducer { stack: self.stack }, consumer)
+    }
+}
+
+/// A splittable unit of `rayon` work wrapping a slice of the walk stack.
+///
+/// `split` only ever divides the stack it was handed at construction (i.e.
+/// the top-level glob groups); once a thread starts `fold_with`, any child
+/// `NodeWalker`s discovered along the way (e.g. `**` descending into a
+/// subdirectory) are pushed onto that call's own local stack and drained by
+/// the same thread to completion, exactly as the sequential [`Iterator`]
+/// implementation does -- they are never split back out for other threads to
+/// steal. So a single glob group's subtree is always walked on one thread;
+/// only the top-level groups are distributed across the pool.
+struct NodeWalkerProducer {
+    stack: Vec<NodeWalker>,
+}
+
+impl UnindexedProducer for NodeWalkerProducer {
+    type Item = io::Result<DirEntry>;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if self.stack.len() <= 1 {
+            return (self, None);
+        }
+        let half = self.stack.split_off(self.stack.len() / 2);
+        (self, Some(Self { stack: half }))
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let mut stack = self.stack;
+        while let Some(walker) = stack.last_mut() {
+            match walker.next() {
+                None => _ = stack.pop(),
+                Some(Err(err)) => {
+                    folder = folder.consume(Err(err));
+                    if folder.full() {
+                        return folder;
+                    }
+                }
+                Some(Ok(mut out)) => {
+                    stack.append(&mut out.nodes);
+                    if let Some((terminal, _origins)) = out.terminal {
+                        folder = folder.consume(Ok(terminal));
+                        if folder.full() {
+                            return folder;
+                        }
+                    }
+                }
+            }
+        }
+        folder
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use insta::assert_debug_snapshot;
@@ -463,4 +1115,24 @@ mod tests {
             assert_debug_snapshot!(cnode);
         });
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_plan_node_invalid_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        // a pure-path component may contain arbitrary bytes...
+        let path_part = OsStr::from_bytes(b"b\xFFd");
+        let node = WalkPlanNode::build(&[path_part]);
+        assert!(WalkPlanNodeCompiled::new(&node, false).is_ok());
+
+        // ...but a glob-like component must be valid UTF-8, since globset requires it.
+        let glob_part = OsStr::from_bytes(b"b\xFF*");
+        let node = WalkPlanNode::build(&[glob_part]);
+        assert!(matches!(
+            WalkPlanNodeCompiled::new(&node, false),
+            Err(crate::Error::InvalidUtf8(_))
+        ));
+        assert!(WalkPlanNodeCompiled::new(&node, true).is_ok());
+    }
 }